@@ -0,0 +1,287 @@
+//! An unbounded, lazily-growing append only vector.
+//!
+//! Unlike [`AppendArray`](crate::AppendArray), `AppendVec` never runs out of
+//! room: instead of one fixed allocation it holds a fixed table of
+//! lazily-allocated buckets (bucket `i` holding `FIRST_BUCKET_LEN << i`
+//! slots, modeled on boxcar's bucket scheme), so indexing is still just
+//! "decompose the index, load a bucket pointer": readers take no lock, and
+//! besides that pointer load they only touch the atomics `len()` uses to
+//! work out how much of the vec is visible. `append` publishes its slot with a per-slot `init` flag and
+//! returns immediately, the same ready-stamp/committed-watermark scheme
+//! `AppendArray` uses, so one stalled writer can't block every writer after
+//! it.
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Index;
+use core::ptr;
+
+use crate::sync::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// Number of slots in the first bucket. Bucket `i` holds
+/// `FIRST_BUCKET_LEN << i` slots.
+const FIRST_BUCKET_LEN: usize = 8;
+
+/// Enough buckets to cover every index representable by a `usize`.
+const NUM_BUCKETS: usize = usize::BITS as usize - FIRST_BUCKET_LEN.trailing_zeros() as usize;
+
+/// Decompose a global index into the bucket that holds it, that bucket's
+/// length, and the element's offset within the bucket. All bit tricks, no
+/// division.
+fn location(index: usize) -> (usize, usize, usize) {
+    let i = index + FIRST_BUCKET_LEN;
+    let bucket =
+        (usize::BITS - 1 - i.leading_zeros()) as usize - FIRST_BUCKET_LEN.trailing_zeros() as usize;
+    let bucket_len = FIRST_BUCKET_LEN << bucket;
+    let offset = i - bucket_len;
+    (bucket, bucket_len, offset)
+}
+
+struct Slot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    init: AtomicBool,
+}
+
+fn bucket_layout<T>(bucket_len: usize) -> Layout {
+    Layout::array::<Slot<T>>(bucket_len).unwrap()
+}
+
+/// A thread safe append only vector with no fixed capacity.
+///
+/// `append` behaves like [`AppendArray::append`](crate::AppendArray::append)
+/// except it never returns `ArrayFull`: instead of claiming a slot in one
+/// fixed allocation, it claims a slot in a lazily allocated bucket, growing
+/// the backing storage as needed.
+pub struct AppendVec<T> {
+    ticket: AtomicUsize,
+    // The committed watermark: the length of the contiguous, fully
+    // initialized prefix visible to readers. `len()` advances this on
+    // demand instead of every writer racing to bump it, the same scheme
+    // `AppendArray` uses.
+    committed: AtomicUsize,
+    buckets: [AtomicPtr<Slot<T>>; NUM_BUCKETS],
+}
+
+// Same reasoning as `AppendArray`: bound to Send + Sync so we can't be
+// handed something like a `RefCell` that isn't safe to share this way.
+unsafe impl<T: Send + Sync> Send for AppendVec<T> {}
+unsafe impl<T: Send + Sync> Sync for AppendVec<T> {}
+
+impl<T> Default for AppendVec<T> {
+    fn default() -> Self {
+        AppendVec {
+            ticket: AtomicUsize::new(0),
+            committed: AtomicUsize::new(0),
+            buckets: [(); NUM_BUCKETS].map(|_| AtomicPtr::new(ptr::null_mut())),
+        }
+    }
+}
+
+impl<T> AppendVec<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The length of the contiguous, fully initialized prefix of the vec
+    /// that's safe for readers to see. Advances the committed watermark as
+    /// far as consecutive slots' `init` flags allow.
+    pub fn len(&self) -> usize {
+        let mut committed = self.committed.load(Ordering::Acquire);
+        loop {
+            if !self.slot_ready(committed) {
+                return committed;
+            }
+            match self.committed.compare_exchange_weak(
+                committed,
+                committed + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => committed += 1,
+                Err(actual) => committed = actual,
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn slot_ready(&self, index: usize) -> bool {
+        let (bucket, _, offset) = location(index);
+        let ptr = self.buckets[bucket].load(Ordering::Acquire);
+        !ptr.is_null() && unsafe { (*ptr.add(offset)).init.load(Ordering::Acquire) }
+    }
+
+    /// Append an element to the end of the vector, returning its index.
+    pub fn append(&self, item: T) -> usize {
+        let ticket = self.ticket.fetch_add(1, Ordering::Relaxed);
+        let (bucket, bucket_len, offset) = location(ticket);
+        let slots = self.get_or_alloc_bucket(bucket, bucket_len);
+
+        // Store the value and flip this slot's `init` flag, without waiting
+        // on any earlier ticket. A writer that's off allocating a bucket (or
+        // just slow) no longer holds up every ticket issued after it; `len()`
+        // reconciles the flags into a contiguous visible prefix later.
+        unsafe {
+            let slot = &*slots.add(offset);
+            (*slot.value.get()).write(item);
+            slot.init.store(true, Ordering::Release);
+        }
+
+        ticket
+    }
+
+    fn get_or_alloc_bucket(&self, bucket: usize, bucket_len: usize) -> *mut Slot<T> {
+        let slot = &self.buckets[bucket];
+        let ptr = slot.load(Ordering::Acquire);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        let layout = bucket_layout::<T>(bucket_len);
+        let new_ptr = unsafe { alloc(layout) } as *mut Slot<T>;
+        assert!(!new_ptr.is_null(), "allocation failure");
+        for i in 0..bucket_len {
+            unsafe {
+                new_ptr.add(i).write(Slot {
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                    init: AtomicBool::new(false),
+                });
+            }
+        }
+
+        match slot.compare_exchange(
+            ptr::null_mut(),
+            new_ptr,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => new_ptr,
+            Err(existing) => {
+                // Lost the race: free the bucket we just allocated.
+                unsafe { dealloc(new_ptr as *mut u8, layout) };
+                existing
+            }
+        }
+    }
+}
+
+impl<T> Index<usize> for AppendVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        assert!(index < self.len(), "index out of bounds");
+        let (bucket, _, offset) = location(index);
+        let ptr = self.buckets[bucket].load(Ordering::Acquire);
+        unsafe { (*(*ptr.add(offset)).value.get()).assume_init_ref() }
+    }
+}
+
+impl<T> Drop for AppendVec<T> {
+    fn drop(&mut self) {
+        for bucket in 0..NUM_BUCKETS {
+            let ptr = self.buckets[bucket].load(Ordering::Relaxed);
+            if ptr.is_null() {
+                continue;
+            }
+            let bucket_len = FIRST_BUCKET_LEN << bucket;
+            unsafe {
+                for i in 0..bucket_len {
+                    let slot = &*ptr.add(i);
+                    if slot.init.load(Ordering::Relaxed) {
+                        (*slot.value.get()).assume_init_drop();
+                    }
+                }
+                dealloc(ptr as *mut u8, bucket_layout::<T>(bucket_len));
+            }
+        }
+    }
+}
+
+impl<T> core::fmt::Debug for AppendVec<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AppendVec")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+// Same reasoning as `lib.rs`'s `mod tests`: these run outside of
+// `loom::model(..)`, so they can't build under `--cfg loom` against
+// loom-backed atomics.
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::hint::black_box;
+    use std::sync::atomic::AtomicUsize as StdAtomicUsize;
+
+    #[test]
+    fn it_works() {
+        let vec = AppendVec::<u32>::new();
+        let idx_0 = vec.append(31);
+        let idx_1 = vec.append(35);
+        assert_eq!(vec[idx_0], 31);
+        assert_eq!(idx_0, 0);
+        assert_eq!(vec[idx_1], 35);
+        assert_eq!(idx_1, 1);
+        assert_eq!(vec.len(), 2);
+    }
+
+    #[test]
+    fn stress() {
+        #[cfg(not(miri))]
+        const ITERS: usize = 0x1_000;
+        #[cfg(miri)]
+        const ITERS: usize = 0x10;
+        const THREADS: usize = 8;
+        const TOTAL: usize = ITERS * THREADS;
+        // crosses several bucket boundaries, unlike a fixed-size AppendArray
+        let vec = AppendVec::<usize>::new();
+        std::thread::scope(|s| {
+            let vec = &vec;
+            for i in 0..THREADS {
+                s.spawn(move || {
+                    for j in 0..ITERS {
+                        vec.append(i * ITERS + j);
+                    }
+                });
+                s.spawn(move || {
+                    for _ in 0..ITERS {
+                        black_box(vec.len());
+                    }
+                });
+            }
+        });
+        assert_eq!(vec.len(), TOTAL);
+        for i in 0..TOTAL {
+            assert!((0..TOTAL).any(|idx| vec[idx] == i));
+        }
+    }
+
+    struct ToDrop<'a>(&'a StdAtomicUsize);
+    impl Drop for ToDrop<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn dropping() {
+        let count = StdAtomicUsize::new(0);
+        // span more than one bucket (FIRST_BUCKET_LEN == 8) so both a full
+        // bucket and a partially-used one get exercised.
+        const TOTAL: usize = 20;
+        {
+            let vec = AppendVec::<ToDrop>::new();
+            for _ in 0..TOTAL {
+                vec.append(ToDrop(&count));
+            }
+            assert_eq!(vec.len(), TOTAL);
+        }
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), TOTAL);
+    }
+}
@@ -1,15 +1,21 @@
 #![no_std]
-#![feature(maybe_uninit_uninit_array)]
 //! A Thread safe append only array with a fixed size. Allows reader's to read
 //! from the array with no atomic operations.
 
+#[cfg(feature = "alloc")]
+mod append_vec;
+#[cfg(feature = "alloc")]
+pub use append_vec::AppendVec;
+
+mod sync;
+
 use core::cell::UnsafeCell;
 use core::default::Default;
 use core::fmt::Debug;
 use core::mem::MaybeUninit;
 use core::ops::{Deref, Drop};
 use core::result::Result;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::{AtomicBool, AtomicUsize, Ordering};
 
 #[derive(Debug, PartialEq)]
 pub enum AppendArrayError {
@@ -19,7 +25,14 @@ pub enum AppendArrayError {
 #[derive(Debug)]
 pub struct AppendArray<T, const N: usize> {
     ticket: AtomicUsize,
-    len: AtomicUsize,
+    // The committed watermark: the length of the contiguous, fully
+    // initialized prefix of `array` that readers may observe. `len()`
+    // advances this on demand instead of every writer racing to bump it.
+    committed: AtomicUsize,
+    // One flag per slot, set by the writer that claimed it once its value
+    // is written. Lets later writers finish and return without waiting on
+    // an earlier, still in-flight writer.
+    ready: [AtomicBool; N],
     array: [MaybeUninit<UnsafeCell<T>>; N],
 }
 
@@ -31,36 +44,95 @@ unsafe impl<T: Send + Sync, const N: usize> Sync for AppendArray<T, N> {}
 impl<T, const N: usize> Deref for AppendArray<T, N> {
     type Target = [T];
     fn deref(&self) -> &Self::Target {
-        unsafe {
-            core::slice::from_raw_parts(
-                self.array.as_ptr() as *const T,
-                self.len.load(Ordering::Acquire),
-            )
+        unsafe { core::slice::from_raw_parts(self.array.as_ptr() as *const T, self.len()) }
+    }
+}
+
+// `[const { .. }; N]` (as used by `new()` below) builds each element in
+// place with no stack copy, but loom's atomics aren't `const fn`, so under
+// `--cfg loom` we fall back to the `.map` form instead. That's fine there:
+// loom-backed tests only ever use small N, so the stack cost `new()` is
+// built to avoid doesn't come up.
+#[cfg(loom)]
+impl<T, const N: usize> Default for AppendArray<T, N> {
+    fn default() -> Self {
+        AppendArray {
+            ticket: AtomicUsize::new(0),
+            committed: AtomicUsize::new(0),
+            ready: [(); N].map(|_| AtomicBool::new(false)),
+            array: [(); N].map(|_| MaybeUninit::uninit()),
         }
     }
 }
 
+#[cfg(not(loom))]
 impl<T, const N: usize> Default for AppendArray<T, N> {
     fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(loom))]
+impl<T, const N: usize> AppendArray<T, N> {
+    /// Create an empty `AppendArray`, usable in `const` and `static`
+    /// initializers so it can be placed in static storage without an
+    /// allocator or a lazy-init cell, e.g.:
+    ///
+    /// ```ignore
+    /// static LOG: AppendArray<Event, 256> = AppendArray::new();
+    /// ```
+    pub const fn new() -> Self {
         AppendArray {
             ticket: AtomicUsize::new(0),
-            len: AtomicUsize::new(0),
-            array: MaybeUninit::uninit_array(),
+            committed: AtomicUsize::new(0),
+            ready: [const { AtomicBool::new(false) }; N],
+            array: [const { MaybeUninit::uninit() }; N],
         }
     }
 }
 
 impl<T, const N: usize> Drop for AppendArray<T, N> {
     fn drop(&mut self) {
-        for i in 0..self.len.load(Ordering::Relaxed) {
-            unsafe {
-                self.array[i].assume_init_drop();
+        // Slots may be written (and `ready`) past the last committed
+        // watermark if their writer never got around to advancing it, so
+        // drop every initialized slot the ticket counter knows about, not
+        // just the committed prefix.
+        for i in 0..self.ticket.load(Ordering::Relaxed).min(N) {
+            if self.ready[i].load(Ordering::Relaxed) {
+                unsafe {
+                    self.array[i].assume_init_drop();
+                }
             }
         }
     }
 }
 
 impl<T, const N: usize> AppendArray<T, N> {
+    /// The length of the contiguous, fully initialized prefix of the array
+    /// that's safe for readers to see. Advances the committed watermark as
+    /// far as consecutive `ready` slots allow.
+    pub fn len(&self) -> usize {
+        let mut committed = self.committed.load(Ordering::Acquire);
+        loop {
+            if committed >= N || !self.ready[committed].load(Ordering::Acquire) {
+                return committed;
+            }
+            match self.committed.compare_exchange_weak(
+                committed,
+                committed + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => committed += 1,
+                Err(actual) => committed = actual,
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Append an element to the end of the array, returns the index of the
     /// element or an error if the array is full.
     pub fn append(&self, item: T) -> Result<usize, AppendArrayError> {
@@ -78,26 +150,154 @@ impl<T, const N: usize> AppendArray<T, N> {
             UnsafeCell::raw_get(self.array[ticket].as_ptr()).write(item);
         }
 
-        // Another thread may not be done writing its item, we need to wait for
-        // it to increase the length of the array before we do, otherwise a
-        // reader could read an uninitialized value from the array.
-        while self.len.load(Ordering::Relaxed) != ticket {
-            core::hint::spin_loop();
-        }
-
-        // The item is in the array and it's now our turn to increase the length
-        self.len.fetch_add(1, Ordering::Release);
+        // Publish our own slot and return immediately: we don't wait on
+        // earlier writers, so one slow writer can no longer stall everyone
+        // after it. `len()` is what stitches per-slot flags back into a
+        // contiguous visible prefix.
+        self.ready[ticket].store(true, Ordering::Release);
 
         // Return the index of the item we just inserted
         Ok(ticket)
     }
+
+    /// Append every element of `items`, reserving their indices as a single
+    /// contiguous run, and returns the index of the first element.
+    ///
+    /// This amortizes the reservation over the whole batch, with one
+    /// `fetch_add` instead of one per element (each slot's `ready` flag is
+    /// still set on its own), and keeps the batch's elements contiguous
+    /// instead of interleaved with other writers.
+    pub fn append_slice(&self, items: &[T]) -> Result<usize, AppendArrayError>
+    where
+        T: Clone,
+    {
+        self.append_n(items.len(), |i| items[i].clone())
+    }
+
+    /// Reserve `count` contiguous slots and fill them by calling `f(offset)`
+    /// for each `offset` in `0..count`, returning the index of the first
+    /// element.
+    pub fn append_n(
+        &self,
+        count: usize,
+        mut f: impl FnMut(usize) -> T,
+    ) -> Result<usize, AppendArrayError> {
+        // Reserve the whole run with a single fetch_add.
+        let start = self.ticket.fetch_add(count, Ordering::Relaxed);
+
+        if start + count > N {
+            self.ticket.fetch_sub(count, Ordering::Relaxed);
+            return Err(AppendArrayError::ArrayFull);
+        }
+
+        for i in 0..count {
+            unsafe {
+                UnsafeCell::raw_get(self.array[start + i].as_ptr()).write(f(i));
+            }
+            self.ready[start + i].store(true, Ordering::Release);
+        }
+
+        Ok(start)
+    }
+
+    /// Reserve the next slot without materializing `T` on the stack first,
+    /// so large values can be built directly in place.
+    ///
+    /// Write the value through [`Reservation::slot`] and call
+    /// [`Reservation::commit`] to publish it.
+    pub fn try_reserve(&self) -> Result<Reservation<'_, T, N>, AppendArrayError> {
+        let ticket = self.ticket.fetch_add(1, Ordering::Relaxed);
+
+        if ticket >= N {
+            self.ticket.fetch_sub(1, Ordering::Relaxed);
+            return Err(AppendArrayError::ArrayFull);
+        }
+
+        Ok(Reservation {
+            array: self,
+            index: ticket,
+            committed: false,
+        })
+    }
+
+    /// Append an element built directly into its slot by `f`, avoiding the
+    /// extra move `append` pays to get `item` there.
+    pub fn append_with(&self, f: impl FnOnce() -> T) -> Result<usize, AppendArrayError> {
+        let mut reservation = self.try_reserve()?;
+        reservation.slot().write(f());
+        // Safety: we just fully initialized the slot above.
+        Ok(unsafe { reservation.commit() })
+    }
+}
+
+/// A claimed but not yet published slot, returned by
+/// [`AppendArray::try_reserve`].
+///
+/// Dropping a `Reservation` without calling [`commit`](Reservation::commit)
+/// rolls its ticket back if no later writer has claimed one since, so the
+/// slot can be reused; otherwise the slot is left permanently unpublished,
+/// since the contiguous-prefix invariant `len()` relies on gives no safe way
+/// to skip over a gap. A value written through [`slot`](Reservation::slot)
+/// but never committed is not dropped.
+pub struct Reservation<'a, T, const N: usize> {
+    array: &'a AppendArray<T, N>,
+    index: usize,
+    committed: bool,
+}
+
+impl<T, const N: usize> Reservation<'_, T, N> {
+    /// The index this reservation will publish to once committed.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The raw storage for this slot. Write the value through this before
+    /// calling [`commit`](Self::commit).
+    pub fn slot(&mut self) -> &mut MaybeUninit<T> {
+        unsafe {
+            let ptr = UnsafeCell::raw_get(self.array.array[self.index].as_ptr());
+            &mut *(ptr as *mut MaybeUninit<T>)
+        }
+    }
+
+    /// Publish the value written into [`slot`](Self::slot), returning its
+    /// index.
+    ///
+    /// # Safety
+    /// The caller must have fully initialized the slot via
+    /// [`slot`](Self::slot) first.
+    pub unsafe fn commit(mut self) -> usize {
+        self.array.ready[self.index].store(true, Ordering::Release);
+        self.committed = true;
+        self.index
+    }
+}
+
+impl<T, const N: usize> Drop for Reservation<'_, T, N> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        // Best effort: only safe to free the ticket if nobody has claimed
+        // one past ours yet.
+        let _ = self.array.ticket.compare_exchange(
+            self.index + 1,
+            self.index,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
 }
 
 #[cfg(test)]
 #[macro_use]
 extern crate std;
 
-#[cfg(test)]
+// Plain (non-loom) unit tests call into the loom-backed `sync` atomics too
+// when built under `--cfg loom`, but outside of `loom::model(..)` - loom
+// panics the moment one of its atomics is touched outside a model. Only
+// `loom_tests` below is meant to run under that cfg.
+#[cfg(all(test, not(loom)))]
 mod tests {
     use super::*;
     use std::boxed::Box;
@@ -156,6 +356,10 @@ mod tests {
         const ITERS: usize = 0x10;
         const THREADS: usize = 8;
         const TOTAL: usize = ITERS * THREADS;
+        // The large, unread payload is the point: it's what `append` has to
+        // move into the slot, matching the `Data::Lmao` motivating case for
+        // `try_reserve`/`append_with`.
+        #[allow(dead_code, clippy::large_enum_variant)]
         enum Data {
             Ayy(Vec<u8>),
             Lmao([u8; 0x100]),
@@ -208,6 +412,88 @@ mod tests {
         assert_eq!(array[..], [1]);
     }
 
+    #[test]
+    fn append_slice_is_contiguous() {
+        let array = AppendArray::<u32, 8>::default();
+        array.append(1).unwrap();
+        let start = array.append_slice(&[2, 3, 4]).unwrap();
+        assert_eq!(start, 1);
+        assert_eq!(&array[..], [1, 2, 3, 4]);
+        assert_eq!(array.append_n(2, |i| i as u32 + 10).unwrap(), 4);
+        assert_eq!(&array[..], [1, 2, 3, 4, 10, 11]);
+    }
+
+    #[test]
+    fn append_slice_reports_full() {
+        let array = AppendArray::<u32, 2>::default();
+        assert_eq!(
+            array.append_slice(&[1, 2, 3]),
+            Err(AppendArrayError::ArrayFull)
+        );
+        // The failed reservation must not have consumed any tickets.
+        assert_eq!(array.append_slice(&[1, 2]), Ok(0));
+    }
+
+    #[test]
+    fn try_reserve_and_append_with() {
+        let array = AppendArray::<[u8; 0x100], 4>::default();
+
+        let idx = array.append_with(|| [7; 0x100]).unwrap();
+        assert_eq!(idx, 0);
+        assert_eq!(array[0], [7; 0x100]);
+
+        let mut reservation = array.try_reserve().unwrap();
+        assert_eq!(reservation.index(), 1);
+        reservation.slot().write([9; 0x100]);
+        let idx = unsafe { reservation.commit() };
+        assert_eq!(idx, 1);
+        assert_eq!(array[1], [9; 0x100]);
+        assert_eq!(array.len(), 2);
+    }
+
+    #[test]
+    fn dropping_an_uncommitted_reservation_frees_its_ticket() {
+        let array = AppendArray::<u32, 2>::default();
+        {
+            let _reservation = array.try_reserve().unwrap();
+            // Dropped without committing.
+        }
+        // Nobody claimed a ticket in the meantime, so it was rolled back
+        // and is available for reuse.
+        assert_eq!(array.append(1).unwrap(), 0);
+        assert_eq!(array.append(2).unwrap(), 1);
+    }
+
+    #[test]
+    fn append_does_not_block_on_earlier_writer() {
+        let array = AppendArray::<u32, 4>::default();
+
+        // Claim ticket 0 by hand but don't publish it yet, simulating a
+        // writer that's slow to finish.
+        let stalled = array.ticket.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(stalled, 0);
+
+        // A later writer must still be able to finish its own append and
+        // get its index back, instead of spinning on the stalled slot.
+        let idx = array.append(99).unwrap();
+        assert_eq!(idx, 1);
+
+        // Nothing is visible yet: the committed prefix can't skip over the
+        // still-unpublished slot 0.
+        assert_eq!(array.len(), 0);
+
+        // Finish the stalled write out of band.
+        unsafe {
+            UnsafeCell::raw_get(array.array[0].as_ptr()).write(1);
+        }
+        array.ready[0].store(true, Ordering::Release);
+
+        // Now both slots become visible as one contiguous prefix.
+        assert_eq!(array.len(), 2);
+        assert_eq!(array[0], 1);
+        assert_eq!(array[1], 99);
+    }
+
     #[test]
     #[should_panic]
     fn oob() {
@@ -251,3 +537,69 @@ mod tests {
     //     Ok(())
     // }
 }
+
+// Model-checked counterparts to a few of the tests above: instead of hoping a
+// stress test happens to hit a bad interleaving, `loom` exhaustively explores
+// thread preemptions to check the `Acquire`/`Release`/`Relaxed` pairing
+// between `append` and `len`/`Deref`. Run with:
+//   RUSTFLAGS="--cfg loom" cargo test --lib
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::thread;
+
+    #[test]
+    fn it_works() {
+        loom::model(|| {
+            let array = AppendArray::<u32, 2>::default();
+            let idx_0 = array.append(31).unwrap();
+            let idx_1 = array.append(35).unwrap();
+            assert_eq!(array[idx_0], 31);
+            assert_eq!(idx_0, 0);
+            assert_eq!(array[idx_1], 35);
+            assert_eq!(idx_1, 1);
+            assert_eq!(array.len(), 2);
+        });
+    }
+
+    #[test]
+    fn stress_3() {
+        loom::model(|| {
+            const THREADS: usize = 2;
+            let array = loom::sync::Arc::new(AppendArray::<u32, 1>::default());
+            array.append(1).unwrap();
+            let handles: std::vec::Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let array = array.clone();
+                    thread::spawn(move || {
+                        let _ = array.append(2);
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(array[..], [1]);
+        });
+    }
+
+    #[test]
+    fn dropping() {
+        struct ToDrop<'a>(&'a AtomicUsize);
+        impl Drop for ToDrop<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        loom::model(|| {
+            let count = AtomicUsize::new(0);
+            {
+                let array = AppendArray::<ToDrop, 2>::default();
+                array.append(ToDrop(&count)).unwrap();
+                array.append(ToDrop(&count)).unwrap();
+            }
+            assert_eq!(count.load(Ordering::Relaxed), 2);
+        });
+    }
+}
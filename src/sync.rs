@@ -0,0 +1,27 @@
+//! Indirection layer so the atomics backing `AppendArray` can be swapped out:
+//!
+//! - under `--cfg loom`, for `loom`'s model-checked atomics, so the
+//!   `Acquire`/`Release`/`Relaxed` pairing between `append` and `len`/`Deref`
+//!   can be exhaustively checked across thread interleavings instead of only
+//!   spot-checked by the stress tests.
+//! - with the `portable-atomic` feature, for `portable_atomic`'s atomics, so
+//!   the crate keeps working on targets (e.g. `thumbv6m`) without native
+//!   `AtomicUsize`/CAS, which is exactly where a no_std, fixed-size append
+//!   array is most useful.
+//!
+//! Everything else in the crate goes through these re-exports instead of
+//! `core::sync::atomic` directly.
+
+// `AtomicPtr` is only used by the `alloc`-gated `AppendVec`, so allow it
+// going unused when that feature is off.
+#[cfg(loom)]
+#[allow(unused_imports)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+#[allow(unused_imports)]
+pub(crate) use portable_atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+#[allow(unused_imports)]
+pub(crate) use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};